@@ -0,0 +1,32 @@
+use std::path::PathBuf;
+use thiserror::Error;
+
+/// Errors produced while reading, decoding, or writing FASTQ records.
+#[derive(Error, Debug)]
+pub enum FastxError {
+    #[error("failed to read input file {path}: {source}")]
+    ReadError {
+        path: PathBuf,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync + 'static>,
+    },
+
+    #[error("failed to parse a FASTQ record from {path}: {source}")]
+    ParseError {
+        path: PathBuf,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync + 'static>,
+    },
+
+    #[error(
+        "input files are out of sync: saw {cell} cell record(s), {r1} R1 record(s) and {r2} R2 record(s)"
+    )]
+    RecordCountMismatch { cell: usize, r1: usize, r2: usize },
+
+    #[error("failed to create output file {path}: {source}")]
+    CreateError {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+}
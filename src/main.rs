@@ -1,9 +1,21 @@
+mod error;
+mod stats;
+mod whitelist;
+
 use clap::Parser;
+use error::FastxError;
+use gzp::deflate::Gzip;
+use gzp::par::compress::{ParCompress, ParCompressBuilder};
+use gzp::Compression;
+use gzp::ZWriter;
 use needletail::parse_fastx_reader;
+use niffler::compression::{Format, Level};
 use std::fs::File;
-use std::io::{BufReader, BufWriter, Write};
+use std::io::{self, BufWriter, Read, Write};
 use std::path::{Path, PathBuf};
-use std::process::{Command, Stdio};
+
+/// File extensions `niffler`/`gzp` can decode or encode.
+const COMPRESSION_EXTENSIONS: [&str; 4] = ["gz", "bz2", "xz", "zst"];
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -25,24 +37,54 @@ struct Cli {
 
     #[arg(long)]
     recomp: bool,
-}
 
-fn check_pigz_installed() -> Result<(), String> {
-    match Command::new("pigz").arg("--version").output() {
-        Ok(output) if output.status.success() => Ok(()),
-        _ => Err("Error: `pigz` is not installed or not found in PATH.".to_string()),
-    }
+    /// Number of threads used by the parallel gzip writer
+    #[arg(long, default_value_t = 4)]
+    threads: usize,
+
+    /// Gzip compression level (1-9) used when writing gzip output
+    #[arg(long, default_value_t = 6)]
+    compression_level: u32,
+
+    /// Path to a barcode whitelist file (one barcode per line); corrects cell
+    /// barcodes with a single mismatch and flags the rest as unassigned
+    #[arg(long)]
+    whitelist: Option<PathBuf>,
+
+    /// Skip reads whose barcode can't be assigned to the whitelist, instead of
+    /// tagging them with an all-`N` sentinel barcode
+    #[arg(long)]
+    drop_unassigned: bool,
+
+    /// Start position (inclusive) of the UMI span within the cell read
+    #[arg(long)]
+    umi_from: Option<usize>,
+
+    /// End position (exclusive) of the UMI span within the cell read
+    #[arg(long)]
+    umi_to: Option<usize>,
+
+    /// Optional path to write the run summary (TSV) to; printed to stderr otherwise
+    #[arg(long)]
+    stats: Option<PathBuf>,
+
+    /// Number of top barcodes by read count to include in the run summary
+    #[arg(long, default_value_t = 10)]
+    top_barcodes: usize,
 }
 
+/// A sentinel barcode used to tag reads whose barcode could not be assigned
+/// to the whitelist (unless `--drop-unassigned` is set, in which case such
+/// reads are skipped entirely).
+fn unassigned_sentinel(len: usize) -> String {
+    "N".repeat(len)
+}
 
-fn process_cell_sequence(
-    seq: &[u8],
-    from_char: Option<usize>,
-    to_char: Option<usize>,
-    revcomp: bool,
-) -> String {
-    let start = from_char.unwrap_or(0);
-    let end = to_char.unwrap_or(seq.len());
+/// Clip `seq` to `[from, to)`, optionally reverse-complementing the span.
+/// Used for both the cell barcode span and, when configured, the UMI span.
+fn extract_span(seq: &[u8], from: Option<usize>, to: Option<usize>, revcomp: bool) -> String {
+    let start = from.unwrap_or(0);
+    let end = to.unwrap_or(seq.len());
 
     let clipped = if start < end && end <= seq.len() {
         &seq[start..end]
@@ -93,100 +135,350 @@ fn fastq_record_to_string(id: &[u8], desc: &[u8], seq: &[u8], qual: Option<&[u8]
     s
 }
 
-fn start_pigz_reader(path: &Path) -> std::io::Result<BufReader<std::process::ChildStdout>> {
-    let child = Command::new("pigz")
-        .arg("-dc")
-        .arg(path)
-        .stdout(Stdio::piped())
-        .spawn()?;
-    Ok(BufReader::new(child.stdout.expect("No stdout from pigz")))
+/// Open `path`, sniffing its magic bytes to transparently decode gzip, bzip2,
+/// xz, zstd, or plain text, the way `rasusa` does. Uses `niffler::send` rather
+/// than the plain `niffler::from_path` so the returned reader is `Send`,
+/// which `needletail::parse_fastx_reader` requires.
+fn open_reader(path: &Path) -> Result<Box<dyn Read + Send>, FastxError> {
+    let (reader, _format) = niffler::send::from_path(path).map_err(|e| FastxError::ReadError {
+        path: path.to_path_buf(),
+        source: Box::new(e),
+    })?;
+    Ok(reader)
 }
 
-fn start_pigz_writer(output_path: &Path, threads: usize) -> std::io::Result<(std::process::Child, BufWriter<std::process::ChildStdin>)> {
-    let out_file = File::create(output_path)?;
-    let mut child = Command::new("pigz")
-        .arg("-p").arg(threads.to_string())
-        .arg("-c")
-        .stdin(Stdio::piped())
-        .stdout(Stdio::from(out_file))
-        .spawn()?;
-    let child_stdin = child.stdin
-        .take()
-        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::Other, "Failed to capture pigz stdin"))?;
-
-    Ok((child, BufWriter::new(child_stdin)))
+/// Pick an output compression format from the file extension, e.g. `.fastq.gz` -> `Gzip`.
+fn format_from_extension(path: &Path) -> Format {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("gz") => Format::Gzip,
+        Some("bz2") => Format::Bzip,
+        Some("xz") => Format::Lzma,
+        Some("zst") => Format::Zstd,
+        _ => Format::No,
+    }
 }
 
-fn make_output_name(input_path: &Path) -> PathBuf {
-    let filename = input_path.file_name().unwrap().to_string_lossy();
-    if filename.ends_with(".fastq.gz") {
-        let base = filename.trim_end_matches(".fastq.gz");
-        input_path.with_file_name(format!("{}_cells_added.fastq.gz", base))
-    } else {
-        input_path.with_file_name(format!("{}_cells_added.fastq.gz", filename))
+/// A FASTQ output writer, either `gzp`'s parallel gzip encoder or one of
+/// `niffler`'s single-threaded encoders. Kept as a concrete enum (rather than
+/// erased to `Box<dyn Write>`) so the gzip variant's `finish()` stays callable:
+/// `gzp`'s worker threads only flush their final block and trailer there, not
+/// on a plain `Write::flush()`.
+enum OutputWriter {
+    Gzip(ParCompress<Gzip>),
+    Niffler(Box<dyn Write>),
+}
+
+impl Write for OutputWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            OutputWriter::Gzip(w) => w.write(buf),
+            OutputWriter::Niffler(w) => w.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            OutputWriter::Gzip(w) => w.flush(),
+            OutputWriter::Niffler(w) => w.flush(),
+        }
     }
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let cli = Cli::parse();
+impl OutputWriter {
+    /// Drain and close the writer. For the gzip backend this must run before
+    /// exit or the output is truncated: `gzp`'s worker threads only emit the
+    /// final block and trailer from `ZWriter::finish`, not from `flush`.
+    fn finish(&mut self) -> io::Result<()> {
+        match self {
+            OutputWriter::Gzip(w) => w.finish().map_err(|e| io::Error::other(e.to_string())),
+            OutputWriter::Niffler(w) => w.flush(),
+        }
+    }
+}
+
+/// Open a writer for `path`, choosing the codec from its extension. Gzip output is
+/// produced by the parallel `gzp` backend so `--threads` actually speeds things up;
+/// every other codec goes through `niffler`'s single-threaded encoder.
+fn open_writer(path: &Path, threads: usize, compression_level: u32) -> Result<OutputWriter, FastxError> {
+    let file = File::create(path).map_err(|source| FastxError::CreateError {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    match format_from_extension(path) {
+        Format::Gzip => {
+            let writer: ParCompress<Gzip> = ParCompressBuilder::new()
+                .num_threads(threads.max(1))
+                .map_err(|e| FastxError::CreateError {
+                    path: path.to_path_buf(),
+                    source: io::Error::other(e.to_string()),
+                })?
+                .compression_level(Compression::new(compression_level))
+                .from_writer(file);
+            Ok(OutputWriter::Gzip(writer))
+        }
+        format => {
+            let writer = niffler::get_writer(Box::new(BufWriter::new(file)), format, Level::One)
+                .map_err(|e| FastxError::CreateError {
+                    path: path.to_path_buf(),
+                    source: io::Error::other(e.to_string()),
+                })?;
+            Ok(OutputWriter::Niffler(writer))
+        }
+    }
+}
+
+/// Derive the output path for `input_path`, preserving whichever compression
+/// extension (if any) the input used so `open_writer` can pick a matching
+/// codec: a plain `.fastq` input yields a plain `.fastq` output, a `.fastq.bz2`
+/// input yields `.fastq.bz2`, and so on.
+fn make_output_name(input_path: &Path) -> PathBuf {
+    let stem = input_path.file_stem().unwrap().to_string_lossy().to_string();
+    let ext = input_path.extension().and_then(|ext| ext.to_str());
+
+    let new_name = match ext {
+        Some(ext) if COMPRESSION_EXTENSIONS.contains(&ext) => {
+            // Peel one more extension layer so e.g. "R1.fastq.gz" yields
+            // "R1_cells_added.fastq.gz" rather than "R1.fastq_cells_added.gz".
+            let inner = Path::new(&stem);
+            match (
+                inner.file_stem(),
+                inner.extension().and_then(|e| e.to_str()),
+            ) {
+                (Some(inner_stem), Some(inner_ext)) => format!(
+                    "{}_cells_added.{}.{}",
+                    inner_stem.to_string_lossy(),
+                    inner_ext,
+                    ext
+                ),
+                _ => format!("{}_cells_added.{}", stem, ext),
+            }
+        }
+        Some(ext) => format!("{}_cells_added.{}", stem, ext),
+        None => format!("{}_cells_added", stem),
+    };
+
+    input_path.with_file_name(new_name)
+}
 
-    check_pigz_installed()?;
+fn main() -> Result<(), FastxError> {
+    let cli = Cli::parse();
 
-    let cell_reader = start_pigz_reader(&cli.cell)?;
-    let r1_reader = start_pigz_reader(&cli.r1)?;
+    let cell_reader = open_reader(&cli.cell)?;
+    let r1_reader = open_reader(&cli.r1)?;
     let r2_reader = if let Some(ref r2) = cli.r2 {
-        Some(start_pigz_reader(r2)?)
+        Some(open_reader(r2)?)
     } else {
         None
     };
 
-    let mut cell_parser = parse_fastx_reader(cell_reader)?;
-    let mut r1_parser = parse_fastx_reader(r1_reader)?;
+    let mut cell_parser = parse_fastx_reader(cell_reader).map_err(|e| FastxError::ParseError {
+        path: cli.cell.clone(),
+        source: Box::new(e),
+    })?;
+    let mut r1_parser = parse_fastx_reader(r1_reader).map_err(|e| FastxError::ParseError {
+        path: cli.r1.clone(),
+        source: Box::new(e),
+    })?;
     let mut r2_parser = if let Some(r) = r2_reader {
-        Some(parse_fastx_reader(r)?)
+        Some(
+            parse_fastx_reader(r).map_err(|e| FastxError::ParseError {
+                path: cli.r2.clone().unwrap(),
+                source: Box::new(e),
+            })?,
+        )
     } else {
         None
     };
 
-    let (mut r1_child, mut r1_writer) = start_pigz_writer(&make_output_name(&cli.r1), 4)?;
-    let (r2_child, mut r2_writer) = if let Some(ref r2) = cli.r2 {
-        let (child, writer) = start_pigz_writer(&make_output_name(r2), 4)?;
-        (Some(child) ,Some(writer))
+    let mut r1_writer = open_writer(&make_output_name(&cli.r1), cli.threads, cli.compression_level)?;
+    let mut r2_writer = if let Some(ref r2) = cli.r2 {
+        Some(open_writer(&make_output_name(r2), cli.threads, cli.compression_level)?)
     } else {
-        (None, None)
+        None
     };
 
+    let cell_whitelist = cli
+        .whitelist
+        .as_ref()
+        .map(|path| {
+            whitelist::load_whitelist(path).map_err(|source| FastxError::ReadError {
+                path: path.clone(),
+                source: Box::new(source),
+            })
+        })
+        .transpose()?;
+
+    let r2_expected = cli.r2.is_some();
+    let mut cell_count = 0usize;
+    let mut r1_count = 0usize;
+    let mut r2_count = 0usize;
+    let mut stats = stats::RunStats::new();
+
     loop {
         let cell_rec = match cell_parser.next() {
-            Some(Ok(r)) => r,
-            _ => break,
+            Some(Ok(r)) => {
+                cell_count += 1;
+                Some(r)
+            }
+            Some(Err(e)) => {
+                return Err(FastxError::ParseError {
+                    path: cli.cell.clone(),
+                    source: Box::new(e),
+                })
+            }
+            None => None,
         };
         let r1_rec = match r1_parser.next() {
-            Some(Ok(r)) => r,
-            _ => break,
+            Some(Ok(r)) => {
+                r1_count += 1;
+                Some(r)
+            }
+            Some(Err(e)) => {
+                return Err(FastxError::ParseError {
+                    path: cli.r1.clone(),
+                    source: Box::new(e),
+                })
+            }
+            None => None,
         };
-        let r2_rec = match r2_parser.as_mut().and_then(|p| p.next()) {
-            Some(Ok(r)) => Some(r),
-            _ => None,
+        let r2_rec = match r2_parser.as_mut().map(|p| p.next()) {
+            Some(Some(Ok(r))) => {
+                r2_count += 1;
+                Some(r)
+            }
+            Some(Some(Err(e))) => {
+                return Err(FastxError::ParseError {
+                    path: cli.r2.clone().unwrap(),
+                    source: Box::new(e),
+                })
+            }
+            Some(None) | None => None,
         };
 
-        let cell_seq = process_cell_sequence(&cell_rec.seq(), cli.from_char, cli.to_char, cli.recomp);
-        let cell_bytes = cell_seq.as_bytes();
+        match (cell_rec, r1_rec) {
+            (None, None) => {
+                if r2_expected && r2_rec.is_some() {
+                    return Err(FastxError::RecordCountMismatch {
+                        cell: cell_count,
+                        r1: r1_count,
+                        r2: r2_count,
+                    });
+                }
+                break;
+            }
+            (Some(cell_rec), Some(r1_rec)) => {
+                if r2_expected && r2_rec.is_none() {
+                    return Err(FastxError::RecordCountMismatch {
+                        cell: cell_count,
+                        r1: r1_count,
+                        r2: r2_count,
+                    });
+                }
+
+                stats.record_processed();
 
-        let r1_str = fastq_record_to_string(&r1_rec.id(), cell_bytes, &r1_rec.seq(), r1_rec.qual());
-        r1_writer.write_all(r1_str.as_bytes())?;
+                let mut cell_seq =
+                    extract_span(&cell_rec.seq(), cli.from_char, cli.to_char, cli.recomp);
 
-        if let (Some(writer), Some(r2)) = (&mut r2_writer, r2_rec) {
-            let r2_str = fastq_record_to_string(&r2.id(), cell_bytes, &r2.seq(), r2.qual());
-            writer.write_all(r2_str.as_bytes())?;
+                if let Some(wl) = &cell_whitelist {
+                    match whitelist::correct_barcode(cell_seq.as_bytes(), wl) {
+                        Some(corrected) => {
+                            if corrected != cell_seq.as_bytes() {
+                                stats.record_corrected();
+                            }
+                            cell_seq = String::from_utf8(corrected)
+                                .expect("whitelist barcodes are ASCII");
+                        }
+                        None if cli.drop_unassigned => {
+                            stats.record_unassigned();
+                            continue;
+                        }
+                        None => {
+                            stats.record_unassigned();
+                            cell_seq = unassigned_sentinel(cell_seq.len());
+                        }
+                    }
+                }
+
+                stats.record_written(cell_seq.as_bytes());
+
+                let tag = if cli.umi_from.is_some() || cli.umi_to.is_some() {
+                    let umi_seq =
+                        extract_span(&cell_rec.seq(), cli.umi_from, cli.umi_to, cli.recomp);
+                    format!("CB_{}:UMI_{}", cell_seq, umi_seq)
+                } else {
+                    cell_seq
+                };
+                let cell_bytes = tag.as_bytes();
+
+                let r1_str =
+                    fastq_record_to_string(&r1_rec.id(), cell_bytes, &r1_rec.seq(), r1_rec.qual());
+                r1_writer
+                    .write_all(r1_str.as_bytes())
+                    .map_err(|source| FastxError::CreateError {
+                        path: make_output_name(&cli.r1),
+                        source,
+                    })?;
+
+                if let (Some(writer), Some(r2_rec)) = (&mut r2_writer, r2_rec) {
+                    let r2_str = fastq_record_to_string(
+                        &r2_rec.id(),
+                        cell_bytes,
+                        &r2_rec.seq(),
+                        r2_rec.qual(),
+                    );
+                    writer
+                        .write_all(r2_str.as_bytes())
+                        .map_err(|source| FastxError::CreateError {
+                            path: make_output_name(cli.r2.as_ref().unwrap()),
+                            source,
+                        })?;
+                }
+            }
+            // one of cell/R1 ended while the other still had records: the inputs are desynced.
+            _ => {
+                return Err(FastxError::RecordCountMismatch {
+                    cell: cell_count,
+                    r1: r1_count,
+                    r2: r2_count,
+                })
+            }
         }
     }
 
-    drop(r1_writer);
-    if let Some(writer) = r2_writer { drop(writer); }
+    r1_writer.finish().map_err(|source| FastxError::CreateError {
+        path: make_output_name(&cli.r1),
+        source,
+    })?;
+    if let Some(mut writer) = r2_writer {
+        writer
+            .finish()
+            .map_err(|source| FastxError::CreateError {
+                path: make_output_name(cli.r2.as_ref().unwrap()),
+                source,
+            })?;
+    }
 
-    r1_child.wait()?;
-    if let Some(mut child) = r2_child { child.wait()?; }
+    let has_whitelist = cell_whitelist.is_some();
+    if let Some(stats_path) = &cli.stats {
+        let file = File::create(stats_path).map_err(|source| FastxError::CreateError {
+            path: stats_path.clone(),
+            source,
+        })?;
+        stats
+            .write_report(file, cli.top_barcodes, has_whitelist)
+            .map_err(|source| FastxError::CreateError {
+                path: stats_path.clone(),
+                source,
+            })?;
+    } else {
+        stats
+            .write_report(std::io::stderr(), cli.top_barcodes, has_whitelist)
+            .map_err(|source| FastxError::CreateError {
+                path: PathBuf::from("<stderr>"),
+                source,
+            })?;
+    }
 
     Ok(())
 }
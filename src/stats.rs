@@ -0,0 +1,75 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+/// Read/barcode counters accumulated over a run, printed as a summary at the end.
+#[derive(Default)]
+pub struct RunStats {
+    pairs_processed: usize,
+    pairs_written: usize,
+    corrected: usize,
+    unassigned: usize,
+    barcode_counts: HashMap<Vec<u8>, usize>,
+}
+
+impl RunStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record_processed(&mut self) {
+        self.pairs_processed += 1;
+    }
+
+    pub fn record_written(&mut self, barcode: &[u8]) {
+        self.pairs_written += 1;
+        *self.barcode_counts.entry(barcode.to_vec()).or_insert(0) += 1;
+    }
+
+    pub fn record_corrected(&mut self) {
+        self.corrected += 1;
+    }
+
+    /// A barcode with no unambiguous whitelist match, whether the read was
+    /// dropped (`--drop-unassigned`) or written with an unassigned sentinel.
+    pub fn record_unassigned(&mut self) {
+        self.unassigned += 1;
+    }
+
+    fn distinct_barcodes(&self) -> usize {
+        self.barcode_counts.len()
+    }
+
+    fn top_barcodes(&self, n: usize) -> Vec<(&[u8], usize)> {
+        let mut counts: Vec<(&[u8], usize)> = self
+            .barcode_counts
+            .iter()
+            .map(|(barcode, count)| (barcode.as_slice(), *count))
+            .collect();
+        counts.sort_by_key(|b| std::cmp::Reverse(b.1));
+        counts.truncate(n);
+        counts
+    }
+
+    /// Write a TSV run summary: totals, distinct barcode count, whitelist
+    /// corrected/unassigned counts (if a whitelist was used), then the top `top_n`
+    /// barcodes by read count.
+    pub fn write_report<W: Write>(
+        &self,
+        mut out: W,
+        top_n: usize,
+        has_whitelist: bool,
+    ) -> io::Result<()> {
+        writeln!(out, "read_pairs_processed\t{}", self.pairs_processed)?;
+        writeln!(out, "read_pairs_written\t{}", self.pairs_written)?;
+        writeln!(out, "distinct_barcodes\t{}", self.distinct_barcodes())?;
+        if has_whitelist {
+            writeln!(out, "barcodes_corrected\t{}", self.corrected)?;
+            writeln!(out, "reads_unassigned\t{}", self.unassigned)?;
+        }
+        writeln!(out, "# top {} barcodes by read count", top_n)?;
+        for (barcode, count) in self.top_barcodes(top_n) {
+            writeln!(out, "{}\t{}", String::from_utf8_lossy(barcode), count)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,96 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+const BASES: [u8; 4] = [b'A', b'C', b'G', b'T'];
+
+/// Load a barcode whitelist, one barcode per line (e.g. a 10x-style allowlist).
+pub fn load_whitelist(path: &Path) -> io::Result<HashSet<Vec<u8>>> {
+    let content = fs::read_to_string(path)?;
+    Ok(content
+        .lines()
+        .map(|line| line.trim().as_bytes().to_vec())
+        .filter(|line| !line.is_empty())
+        .collect())
+}
+
+/// Correct `barcode` against `whitelist`, allowing a single base substitution.
+///
+/// Returns the matching whitelist entry if `barcode` is already a member, or if
+/// exactly one single-substitution neighbor (each of A/C/G/T other than the
+/// base already there, at each of the L positions) is in the whitelist. Returns `None` if no neighbor matches or more
+/// than one does, since the barcode is then ambiguous.
+pub fn correct_barcode(barcode: &[u8], whitelist: &HashSet<Vec<u8>>) -> Option<Vec<u8>> {
+    if whitelist.contains(barcode) {
+        return Some(barcode.to_vec());
+    }
+
+    let mut matches = Vec::new();
+    for i in 0..barcode.len() {
+        for &base in BASES.iter() {
+            if base == barcode[i] {
+                continue;
+            }
+            let mut candidate = barcode.to_vec();
+            candidate[i] = base;
+            if whitelist.contains(&candidate) {
+                matches.push(candidate);
+            }
+        }
+    }
+
+    if matches.len() == 1 {
+        matches.pop()
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn whitelist(barcodes: &[&str]) -> HashSet<Vec<u8>> {
+        barcodes.iter().map(|b| b.as_bytes().to_vec()).collect()
+    }
+
+    #[test]
+    fn exact_match_is_kept_as_is() {
+        let wl = whitelist(&["ACGT", "TTTT"]);
+        assert_eq!(correct_barcode(b"ACGT", &wl), Some(b"ACGT".to_vec()));
+    }
+
+    #[test]
+    fn single_mismatch_is_corrected_to_its_one_neighbor() {
+        let wl = whitelist(&["ACGT", "TTTT"]);
+        // differs from "ACGT" at position 0 only, and matches no other entry
+        assert_eq!(correct_barcode(b"CCGT", &wl), Some(b"ACGT".to_vec()));
+    }
+
+    #[test]
+    fn no_neighbor_in_whitelist_is_unassigned() {
+        let wl = whitelist(&["ACGT", "TTTT"]);
+        // three or more mismatches from every whitelist entry
+        assert_eq!(correct_barcode(b"GGGG", &wl), None);
+    }
+
+    #[test]
+    fn ambiguous_neighbor_is_unassigned() {
+        // "ACGA" is a single substitution away from both whitelist entries
+        let wl = whitelist(&["ACGT", "ACGC"]);
+        assert_eq!(correct_barcode(b"ACGA", &wl), None);
+    }
+
+    #[test]
+    fn load_whitelist_skips_blank_lines_and_trims_whitespace() {
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("fastq_add_cell_whitelist_test_{}.txt", std::process::id()));
+        std::fs::write(&path, "ACGT\n\n  TTTT  \n").unwrap();
+
+        let wl = load_whitelist(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(wl, whitelist(&["ACGT", "TTTT"]));
+    }
+}
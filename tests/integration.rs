@@ -1,9 +1,27 @@
 use std::process::Command;
 use std::fs::File;
 use std::io::{BufReader};
+use std::path::{Path, PathBuf};
 use flate2::read::MultiGzDecoder;
 use needletail::parse_fastx_reader;
 
+/// A private, per-test scratch directory under the system temp dir so fixture
+/// files from different tests never collide.
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("fastq_add_cell_test_{}_{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Write a plain-text (uncompressed) FASTQ file with one record per `(id, seq)` pair.
+fn write_fastq(path: &Path, records: &[(&str, &str)]) {
+    let mut content = String::new();
+    for (id, seq) in records {
+        content.push_str(&format!("@{}\n{}\n+\n{}\n", id, seq, "I".repeat(seq.len())));
+    }
+    std::fs::write(path, content).unwrap();
+}
+
 #[test]
 fn test_fastq_add_cell_ids() {
     let cell_path = "testData/Cell.fastq.gz";
@@ -79,3 +97,120 @@ fn test_fastq_add_cell_ids() {
         }
     }
 }
+
+#[test]
+fn test_mismatched_record_counts_is_reported_as_an_error() {
+    let dir = scratch_dir("mismatch");
+    let cell_path = dir.join("cell.fastq");
+    let r1_path = dir.join("r1.fastq");
+    let r2_path = dir.join("r2.fastq");
+
+    // cell/r1 have 2 records each, r2 is truncated to 1: the streams desync
+    // on the second record.
+    write_fastq(&cell_path, &[("read1", "ACGT"), ("read2", "TTTT")]);
+    write_fastq(&r1_path, &[("read1", "AAAACCCC"), ("read2", "GGGGTTTT")]);
+    write_fastq(&r2_path, &[("read1", "CCCCAAAA")]);
+
+    let output = Command::new("target/release/fastq_add_cell")
+        .args(&[
+            "-c",
+            cell_path.to_str().unwrap(),
+            "-1",
+            r1_path.to_str().unwrap(),
+            "-2",
+            r2_path.to_str().unwrap(),
+        ])
+        .output()
+        .expect("failed to execute fastq_add_cell");
+
+    assert!(
+        !output.status.success(),
+        "expected a non-zero exit on desynced record streams"
+    );
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("RecordCountMismatch"),
+        "stderr did not mention RecordCountMismatch: {}",
+        stderr
+    );
+    assert!(stderr.contains("cell: 2"), "stderr: {}", stderr);
+    assert!(stderr.contains("r1: 2"), "stderr: {}", stderr);
+    assert!(stderr.contains("r2: 1"), "stderr: {}", stderr);
+}
+
+#[test]
+fn test_umi_span_is_tagged_alongside_the_cell_barcode() {
+    let dir = scratch_dir("umi");
+    let cell_path = dir.join("cell.fastq");
+    let r1_path = dir.join("r1.fastq");
+
+    // 16bp barcode followed directly by a 4bp UMI.
+    write_fastq(&cell_path, &[("read1", "ACGTACGTACGTACGTAAAA")]);
+    write_fastq(&r1_path, &[("read1", "GGGGCCCC")]);
+
+    let output = Command::new("target/release/fastq_add_cell")
+        .args(&[
+            "-c",
+            cell_path.to_str().unwrap(),
+            "-1",
+            r1_path.to_str().unwrap(),
+            "--from-char",
+            "0",
+            "--to-char",
+            "16",
+            "--umi-from",
+            "16",
+            "--umi-to",
+            "20",
+        ])
+        .output()
+        .expect("failed to execute fastq_add_cell");
+
+    assert!(output.status.success(), "stderr: {}", String::from_utf8_lossy(&output.stderr));
+
+    let out_r1 = dir.join("r1_cells_added.fastq");
+    let content = std::fs::read_to_string(&out_r1).unwrap();
+    let id_line = content.lines().next().unwrap();
+
+    assert!(
+        id_line.contains("CB_ACGTACGTACGTACGT:UMI_AAAA"),
+        "R1 ID did not contain the expected CB/UMI tag: '{}'",
+        id_line
+    );
+}
+
+#[test]
+fn test_stats_report_is_written_to_the_given_path() {
+    let dir = scratch_dir("stats");
+    let cell_path = dir.join("cell.fastq");
+    let r1_path = dir.join("r1.fastq");
+    let stats_path = dir.join("run.stats");
+
+    write_fastq(&cell_path, &[("read1", "ACGT"), ("read2", "ACGT"), ("read3", "TTTT")]);
+    write_fastq(
+        &r1_path,
+        &[("read1", "AAAA"), ("read2", "CCCC"), ("read3", "GGGG")],
+    );
+
+    let output = Command::new("target/release/fastq_add_cell")
+        .args(&[
+            "-c",
+            cell_path.to_str().unwrap(),
+            "-1",
+            r1_path.to_str().unwrap(),
+            "--stats",
+            stats_path.to_str().unwrap(),
+        ])
+        .output()
+        .expect("failed to execute fastq_add_cell");
+
+    assert!(output.status.success(), "stderr: {}", String::from_utf8_lossy(&output.stderr));
+
+    let report = std::fs::read_to_string(&stats_path).unwrap();
+    assert!(report.contains("read_pairs_processed\t3"), "report: {}", report);
+    assert!(report.contains("read_pairs_written\t3"), "report: {}", report);
+    // "ACGT" is seen twice, "TTTT" once: two distinct barcodes.
+    assert!(report.contains("distinct_barcodes\t2"), "report: {}", report);
+    assert!(report.contains("ACGT\t2"), "report: {}", report);
+}